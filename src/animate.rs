@@ -0,0 +1,279 @@
+use clap::Args;
+use clap::ValueEnum;
+use num::Complex;
+use std::sync::Arc;
+use std::thread::available_parallelism;
+use std::fs::File;
+use std::time::Duration;
+use image::codecs::gif::GifEncoder;
+use image::{DynamicImage, Delay, Frame};
+
+use crate::{render_frame, render_frame_equalized, blur_pixels, build_gradient,
+            parse_complex_number, parse_values, ColorStyle, BlendMode};
+
+// value enum for the command line argument parser
+
+#[derive(ValueEnum,Copy,Clone,Debug)]
+pub enum AnimationFormat {
+    Png,
+    Gif,
+}
+
+#[derive(Args, Debug)]
+pub struct AnimateArgs {
+
+    #[clap(short, long="dimensions", default_value="1200x1200", value_name="USIZExUSIZE")]
+    /// Image dimensions
+    dimensions: String,
+
+    #[clap(short='o', long="out", default_value="frame", value_name="FILE")]
+    /// output file (GIF) or filename prefix (PNG sequence)
+    out: String,
+
+    #[clap(long, value_enum, default_value="png")]
+    /// output format
+    format: AnimationFormat,
+
+    #[clap(long="frames", default_value_t=30, value_name="USIZE")]
+    /// number of frames to render
+    frames: usize,
+
+    #[clap(long="frame-delay", default_value_t=100, value_name="MILLISECONDS")]
+    /// delay between frames in the assembled GIF, in milliseconds
+    frame_delay: u32,
+
+    #[clap(long, value_name="FILE")]
+    /// custom color gradient
+    config: Option<String>,
+
+    #[clap(short='c', long="color-style", value_enum, default_value="greyscale")]
+    /// Select color gradient
+    cm: ColorStyle,
+
+    #[clap(long="blend", value_enum, default_value="rgb")]
+    /// color space used to interpolate the gradient
+    blend: BlendMode,
+
+    #[clap(long, value_name="STOPS")]
+    /// inline gradient stops, e.g. '0.0=#05475c;0.4=#0a7873;1.0=#b8d8d7' (overrides --color-style)
+    stops: Option<String>,
+
+    #[clap(long, default_value_t=false)]
+    /// histogram-equalize escape times for even color utilization across the gradient
+    equalize: bool,
+
+    #[clap(long, default_value_t=1.0, value_name="F32")]
+    /// blur (sigma)
+    blur: f32,
+
+    #[clap(short='w', long="power", default_value_t=2, value_name="U8")]
+    /// the 'x' in the equation z^x + c
+    power: u8,
+
+    #[clap(short='n', long="samples", default_value_t=1, value_name="USIZE")]
+    /// supersample each pixel on an n x n sub-pixel grid to smooth jagged edges
+    samples: usize,
+
+    #[clap(long, default_value_t=false)]
+    /// invert color gradient
+    inverse: bool,
+
+    #[clap(long, value_name="USIZE")]
+    /// number of threads (optional), defaults to 'available parallelism'
+    threads: Option<usize>,
+
+    #[clap(long="complex-start", default_value="-0.4,0.6", allow_hyphen_values=true, value_name="F64,F64")]
+    /// the 'c' in the equation z^x + c, at the first frame
+    complex_start: String,
+
+    #[clap(long="complex-end", default_value="-0.4,0.6", allow_hyphen_values=true, value_name="F64,F64")]
+    /// the 'c' in the equation z^x + c, at the last frame
+    complex_end: String,
+
+    #[clap(long="offset-start", default_value="0.0:0.0", allow_hyphen_values=true, value_name="F64:F64")]
+    /// offset at the first frame
+    offset_start: String,
+
+    #[clap(long="offset-end", default_value="0.0:0.0", allow_hyphen_values=true, value_name="F64:F64")]
+    /// offset at the last frame
+    offset_end: String,
+
+    #[clap(long="scale-start", default_value_t=3.0, value_name="F64")]
+    /// scale factor at the first frame
+    scale_start: f64,
+
+    #[clap(long="scale-end", default_value_t=3.0, value_name="F64")]
+    /// scale factor at the last frame
+    scale_end: f64,
+
+    #[clap(long="factor-start", default_value_t=-0.25, allow_hyphen_values=true, value_name="F64")]
+    /// multiplication factor of the secondary julia set at the first frame
+    factor_start: f64,
+
+    #[clap(long="factor-end", default_value_t=-0.25, allow_hyphen_values=true, value_name="F64")]
+    /// multiplication factor of the secondary julia set at the last frame
+    factor_end: f64,
+
+    #[clap(long="gap-start", default_value_t=0.01, allow_hyphen_values=true, value_name="F64")]
+    /// difference between the two rendered julia sets at the first frame
+    gap_start: f64,
+
+    #[clap(long="gap-end", default_value_t=0.01, allow_hyphen_values=true, value_name="F64")]
+    /// difference between the two rendered julia sets at the last frame
+    gap_end: f64,
+
+    #[clap(long="intensity-start", default_value_t=3.0, value_name="F64")]
+    /// overall intensity multiplication factor at the first frame (must stay at its default when --equalize is set)
+    intensity_start: f64,
+
+    #[clap(long="intensity-end", default_value_t=3.0, value_name="F64")]
+    /// overall intensity multiplication factor at the last frame (must stay at its default when --equalize is set)
+    intensity_end: f64,
+}
+
+// linearly interpolate between two f64 values
+
+fn lerp(start: f64, end: f64, t: f64) -> f64 {
+    start + (end - start) * t
+}
+
+// linearly interpolate between two pairs of f64 values
+
+fn lerp_pair(start: (f64, f64), end: (f64, f64), t: f64) -> (f64, f64) {
+    (lerp(start.0, end.0, t), lerp(start.1, end.1, t))
+}
+
+// run the animation subsystem: render an interpolated sequence of frames
+// and encode it either as numbered PNGs or as a single animated GIF
+
+pub fn run(args: AnimateArgs) -> Result<(), Box<dyn std::error::Error>> {
+
+    // parse start/end complex numbers
+    let complex_start = parse_complex_number(&args.complex_start).expect("error parsing start complex number");
+    let complex_end = parse_complex_number(&args.complex_end).expect("error parsing end complex number");
+
+    // parse image dimensions
+    let dimensions: (usize, usize) = parse_values(&args.dimensions, 'x').expect("error parsing image dimensions");
+
+    // get x/y ratio of the image dimensions
+    let ratio = dimensions.0 as f64 / dimensions.1 as f64;
+
+    // parse start/end offsets
+    let offset_start: (f64, f64) = parse_values(&args.offset_start, ':').expect("error parsing start offset");
+    let offset_end: (f64, f64) = parse_values(&args.offset_end, ':').expect("error parsing end offset");
+
+    // '--equalize' normalizes colors across the whole rank domain, so '--intensity-start'/'--intensity-end'
+    // have no effect in that mode; reject the combination instead of silently ignoring the flags
+    if args.equalize && (args.intensity_start != 3.0 || args.intensity_end != 3.0) {
+        return Err("--intensity-start/--intensity-end have no effect together with --equalize".into());
+    }
+
+    // determine number of threads
+    let threads = match args.threads {
+        Some(value) => value,
+        None => available_parallelism()?.get()
+    };
+    eprintln!("Using {} threads.", threads);
+
+    // build color gradient
+    let grad = build_gradient(&args.stops, &args.cm, args.config, &args.blend)?;
+
+    // initialize atomic reference counting for the color gradient
+    // in order to be shared safely between threads
+    let grad_arc = Arc::new(grad);
+
+    // collected GIF frames, only populated when the output format is Gif
+    let mut gif_frames: Vec<Frame> = Vec::new();
+
+    for i in 0..args.frames {
+
+        let t = if args.frames <= 1 { 0.0 } else { i as f64 / (args.frames - 1) as f64 };
+
+        let complex = Complex {
+            re: lerp(complex_start.re, complex_end.re, t),
+            im: lerp(complex_start.im, complex_end.im, t),
+        };
+
+        let parsed_offset = lerp_pair(offset_start, offset_end, t);
+        let scale = lerp(args.scale_start, args.scale_end, t);
+        let factor = lerp(args.factor_start, args.factor_end, t);
+        let gap = lerp(args.gap_start, args.gap_end, t);
+        let intensity = lerp(args.intensity_start, args.intensity_end, t);
+
+        // calculate actual offset in a way that '0:0' will always result in a centered image
+        let off = scale / 2.0;
+        let offset = ((parsed_offset.0 - off) + off * ratio, parsed_offset.1, off);
+
+        // render the frame across all available threads
+        let pixels = if args.equalize {
+            render_frame_equalized(dimensions,
+                                    scale,
+                                    offset,
+                                    complex,
+                                    gap,
+                                    &grad_arc,
+                                    args.inverse,
+                                    args.power as u32,
+                                    factor,
+                                    args.samples,
+                                    threads)
+        } else {
+            render_frame(dimensions,
+                          scale,
+                          offset,
+                          complex,
+                          gap,
+                          &grad_arc,
+                          intensity,
+                          args.inverse,
+                          args.power as u32,
+                          factor,
+                          args.samples,
+                          threads)
+        };
+
+        let blurred = blur_pixels(&pixels, dimensions, args.blur);
+
+        match args.format {
+            AnimationFormat::Png => {
+                let filename = format!("{}_{:04}.png", args.out, i + 1);
+                blurred.save(&filename)?;
+            },
+            AnimationFormat::Gif => {
+                let rgba = DynamicImage::ImageRgb8(blurred).to_rgba8();
+                let delay = Delay::from_saturating_duration(Duration::from_millis(args.frame_delay as u64));
+
+                gif_frames.push(Frame::from_parts(rgba, 0, 0, delay));
+            },
+        }
+
+        eprintln!("rendered frame {}/{}", i + 1, args.frames);
+    }
+
+    if let AnimationFormat::Gif = args.format {
+        let filename = if args.out.ends_with(".gif") { args.out.clone() } else { format!("{}.gif", args.out) };
+        let file = File::create(&filename)?;
+        let mut encoder = GifEncoder::new(file);
+        encoder.encode_frames(gif_frames)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lerp_interpolates_linearly_between_start_and_end() {
+        assert_eq!(lerp(0.0, 10.0, 0.0), 0.0);
+        assert_eq!(lerp(0.0, 10.0, 0.5), 5.0);
+        assert_eq!(lerp(0.0, 10.0, 1.0), 10.0);
+        assert_eq!(lerp(-5.0, 5.0, 0.25), -2.5);
+    }
+
+    #[test]
+    fn lerp_pair_interpolates_both_components() {
+        assert_eq!(lerp_pair((0.0, 10.0), (10.0, 0.0), 0.5), (5.0, 5.0));
+    }
+}
@@ -3,10 +3,14 @@ use image::imageops::blur;
 use num::Complex;
 use std::str::FromStr;
 use std::fs::read_to_string;
+use std::sync::Arc;
 use clap::ValueEnum;
 use colorgrad::Color;
 use getrandom::getrandom;
 
+pub mod animate;
+pub mod serve;
+
 // value enum for the command line argument parser
 
 #[derive(ValueEnum,Copy,Clone,Debug)]
@@ -25,6 +29,29 @@ pub enum ColorStyle {
     Random
 }
 
+// value enum for the command line argument parser
+
+#[derive(ValueEnum,Copy,Clone,Debug)]
+pub enum BlendMode {
+    Rgb,
+    LinearRgb,
+    Oklab,
+    Lab,
+}
+
+// translate the CLI blend mode into the corresponding colorgrad blend mode
+
+pub fn to_colorgrad_mode(mode: &BlendMode) -> colorgrad::BlendMode {
+
+    match mode {
+        BlendMode::Rgb => colorgrad::BlendMode::Rgb,
+        BlendMode::LinearRgb => colorgrad::BlendMode::LinearRgb,
+        BlendMode::Oklab => colorgrad::BlendMode::Oklab,
+        BlendMode::Lab => colorgrad::BlendMode::Lab,
+    }
+
+}
+
 // calculate offset for viewpoint
 
 fn calculate_offset(pixel: (usize, usize), scale: (f64, f64), offset: (f64, f64, f64) ) -> Complex<f64> {
@@ -46,10 +73,12 @@ fn make_smooth(c: Complex<f64>, i: usize) -> f64 {
 
 // plotting algorithm for the julia set
 
-fn escape_time(pixel: (usize, usize), scale: (f64, f64), offset: (f64, f64, f64), c: Complex<f64>, limit: usize, power: u32) -> Option<f64> {
+fn escape_time(pixel: (usize, usize), scale: (f64, f64), offset: (f64, f64, f64), c: Complex<f64>, limit: usize, power: u32, subpixel: (f64, f64)) -> Option<f64> {
+
+    let base = calculate_offset(pixel, scale, offset);
+
+    let mut z = Complex { re: base.re + subpixel.1 * scale.1, im: base.im + subpixel.0 * scale.0 };
 
-    let mut z = calculate_offset(pixel, scale, offset);
-    
     for i in 0..limit {
         
         if z.norm_sqr() > 5.0 {
@@ -95,23 +124,23 @@ fn get_diverged(c: Complex<f64>, diverge: f64) -> (Complex<f64>, Complex<f64>) {
     (c, altered)
 }
 
-// actual render function
+// compute the raw blended escape-time value (the smoothed, pre-gradient quantity) for
+// every pixel in the given band, without colorizing it - the "compute field" stage
 
-pub fn render(pixels: &mut [u8],
+pub fn compute_field(
           bounds: (usize, usize),
           upper_left: (usize, usize),
           scale: (f64, f64),
           offset: (f64, f64, f64),
           complex: Complex<f64>,
           diverge: f64,
-          grad: &colorgrad::Gradient,
-          intensity: f64,
-          inverse: bool,
           power: u32,
           factor: f64,
+          samples: usize,
+          field: &mut [f64],
           )
 {
-    assert!(pixels.len() == bounds.0 * bounds.1 * 3);
+    assert!(field.len() == bounds.0 * bounds.1);
 
     for column in 0..bounds.0 {
         for row in 0..bounds.1 {
@@ -121,31 +150,258 @@ pub fn render(pixels: &mut [u8],
 
             let diverged = get_diverged(complex, diverge);
 
-            let a = escape_time(point, scale, offset, diverged.0, 1024, power).unwrap_or(0.0);
-            let b = escape_time(point, scale, offset, diverged.1, 1024, power).unwrap_or(0.0);
-            
-            let mut x = (a + b * factor) / (1.0 + factor);
+            let value = if samples <= 1 {
+                let a = escape_time(point, scale, offset, diverged.0, 1024, power, (0.0, 0.0)).unwrap_or(0.0);
+                let b = escape_time(point, scale, offset, diverged.1, 1024, power, (0.0, 0.0)).unwrap_or(0.0);
 
-            if inverse {
-                x = 255.0 - x;
-            }
+                (a + b * factor) / (1.0 + factor)
+            } else {
+                let mut sum = 0.0;
+
+                for sx in 0..samples {
+                    for sy in 0..samples {
+                        let subpixel = (
+                            (sx as f64 + 0.5) / samples as f64 - 0.5,
+                            (sy as f64 + 0.5) / samples as f64 - 0.5,
+                        );
+
+                        let a = escape_time(point, scale, offset, diverged.0, 1024, power, subpixel).unwrap_or(0.0);
+                        let b = escape_time(point, scale, offset, diverged.1, 1024, power, subpixel).unwrap_or(0.0);
+
+                        sum += (a + b * factor) / (1.0 + factor);
+                    }
+                }
+
+                sum / (samples * samples) as f64
+            };
+
+            field[row * bounds.0 + column] = value;
+        }
+    }
+}
+
+// colorize a pre-computed field of blended values against a gradient - the "colorize" stage
+
+pub fn colorize_field(pixels: &mut [u8], field: &[f64], grad: &colorgrad::Gradient, intensity: f64, inverse: bool) {
+
+    assert!(pixels.len() == field.len() * 3);
+
+    for (i, &value) in field.iter().enumerate() {
+
+        let mut x = value;
+
+        if inverse {
+            x = 255.0 - x;
+        }
+
+        let newpix: [u8; 4] = grad.reflect_at(x * intensity).to_rgba8();
+
+        for rgb in 0..3 {
+            pixels[i * 3 + rgb] = newpix[rgb];
+        }
+    }
+}
+
+// actual render function - computes the field for the band, then colorizes it in place
+
+pub fn render(pixels: &mut [u8],
+          bounds: (usize, usize),
+          upper_left: (usize, usize),
+          scale: (f64, f64),
+          offset: (f64, f64, f64),
+          complex: Complex<f64>,
+          diverge: f64,
+          grad: &colorgrad::Gradient,
+          intensity: f64,
+          inverse: bool,
+          power: u32,
+          factor: f64,
+          samples: usize,
+          )
+{
+    assert!(pixels.len() == bounds.0 * bounds.1 * 3);
+
+    let mut field = vec![0.0f64; bounds.0 * bounds.1];
+
+    compute_field(bounds, upper_left, scale, offset, complex, diverge, power, factor, samples, &mut field);
+
+    colorize_field(pixels, &field, grad, intensity, inverse);
+}
+
+// render a single frame across all available threads, returning the raw pixel buffer
+
+pub fn render_frame(
+    dimensions: (usize, usize),
+    scale: f64,
+    offset: (f64, f64, f64),
+    complex: Complex<f64>,
+    diverge: f64,
+    grad_arc: &Arc<colorgrad::Gradient>,
+    intensity: f64,
+    inverse: bool,
+    power: u32,
+    factor: f64,
+    samples: usize,
+    threads: usize,
+) -> Vec<u8> {
+
+    let mut pixels = vec![0u8; dimensions.0 * dimensions.1 * 3];
+
+    // scalex is used for both x and y axis in order to mitigate image distortion
+    let scalex = scale / dimensions.1 as f64;
+
+    // determine maximum number of pixel rows per thread
+    let rows_per_band = dimensions.1 / threads + 1;
+
+    {
+        let bands: Vec<&mut [u8]> =
+
+            pixels.chunks_mut(rows_per_band * dimensions.0 * 3).collect();
+
+        crossbeam::scope(|spawner| {
+
+            for (i, band) in bands.into_iter().enumerate() {
+
+                let top = rows_per_band * i;
+
+                let height = band.len() / dimensions.0 / 3;
+
+                let band_upper_left = (0, top);
+
+                let band_bounds = (dimensions.0, height);
 
-            let newpix: [u8; 4] = grad.reflect_at(x * intensity).to_rgba8();
+                let cloned_arc = Arc::clone(grad_arc);
 
-            for rgb in 0..3 {
-                pixels[row * (bounds.0 * 3) + column * 3 + rgb] = newpix[rgb];
+                spawner.spawn(move |_| {
+                        render(band,
+                               band_bounds,
+                               band_upper_left,
+                               (scalex, scalex),
+                               offset,
+                               complex,
+                               diverge,
+                               &cloned_arc,
+                               intensity,
+                               inverse,
+                               power,
+                               factor,
+                               samples);
+                });
             }
+        }).unwrap();
+    }
+
+    pixels
+}
+
+// map every value in a field to its rank (in [0.0, 255.0]) within the cumulative
+// distribution of its own finite values - the global histogram-equalization step
+
+pub fn rank_transform(field: &[f64]) -> Vec<f64> {
+
+    let mut sorted: Vec<f64> = field.iter().copied().filter(|value| value.is_finite()).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let total = sorted.len();
+
+    field.iter().map(|value| {
+        if total == 0 {
+            0.0
+        } else {
+            sorted.partition_point(|&sorted_value| sorted_value < *value) as f64 / total as f64 * 255.0
         }
+    }).collect()
+}
+
+// render a single frame using histogram-equalized (rank-order) coloring: the raw blended
+// escape-time field is computed for the whole frame first (across all threads), a global
+// cumulative distribution of the finite values is built from it, and only then is every
+// pixel colorized according to its rank within that distribution
+//
+// the rank is already spread evenly across the full gradient domain, so unlike `render_frame`
+// it is colorized without the `intensity` multiplier - applying it would re-fold the gradient
+// and undo the even color utilization this mode provides
+
+pub fn render_frame_equalized(
+    dimensions: (usize, usize),
+    scale: f64,
+    offset: (f64, f64, f64),
+    complex: Complex<f64>,
+    diverge: f64,
+    grad_arc: &Arc<colorgrad::Gradient>,
+    inverse: bool,
+    power: u32,
+    factor: f64,
+    samples: usize,
+    threads: usize,
+) -> Vec<u8> {
+
+    let mut field = vec![0.0f64; dimensions.0 * dimensions.1];
+
+    // scalex is used for both x and y axis in order to mitigate image distortion
+    let scalex = scale / dimensions.1 as f64;
+
+    // determine maximum number of pixel rows per thread
+    let rows_per_band = dimensions.1 / threads + 1;
+
+    {
+        let bands: Vec<&mut [f64]> =
+
+            field.chunks_mut(rows_per_band * dimensions.0).collect();
+
+        crossbeam::scope(|spawner| {
+
+            for (i, band) in bands.into_iter().enumerate() {
+
+                let top = rows_per_band * i;
+
+                let height = band.len() / dimensions.0;
+
+                let band_upper_left = (0, top);
+
+                let band_bounds = (dimensions.0, height);
+
+                spawner.spawn(move |_| {
+                        compute_field(band_bounds,
+                                      band_upper_left,
+                                      (scalex, scalex),
+                                      offset,
+                                      complex,
+                                      diverge,
+                                      power,
+                                      factor,
+                                      samples,
+                                      band);
+                });
+            }
+        }).unwrap();
     }
+
+    // map every field value to its rank in [0.0, 255.0] and colorize it
+    let ranked = rank_transform(&field);
+
+    let mut pixels = vec![0u8; dimensions.0 * dimensions.1 * 3];
+
+    colorize_field(&mut pixels, &ranked, grad_arc, 1.0, inverse);
+
+    pixels
 }
 
-// perform minimalistic post processing, save image buffer to file
+// apply minimalistic post processing, return the blurred image buffer
 
-pub fn blur_image(filename: &str, pixels: &[u8], bounds: (usize, usize), sigma: f32) -> Result<(), Box<dyn std::error::Error>> {
+pub fn blur_pixels(pixels: &[u8], bounds: (usize, usize), sigma: f32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
 
     let internalbuf: ImageBuffer<Rgb<u8>, &[u8]> = ImageBuffer::from_raw(bounds.0 as u32, bounds.1 as u32, pixels).unwrap();
 
-    let blurred = blur(&internalbuf, sigma);
+    blur(&internalbuf, sigma)
+
+}
+
+// perform minimalistic post processing, save image buffer to file
+
+pub fn blur_image(filename: &str, pixels: &[u8], bounds: (usize, usize), sigma: f32) -> Result<(), Box<dyn std::error::Error>> {
+
+    let blurred = blur_pixels(pixels, bounds, sigma);
 
     blurred.save(filename)?;
 
@@ -167,70 +423,71 @@ pub fn write_image(filename: &str, pixels: &[u8], bounds: (usize, usize)) -> Res
 }
 */
 
-// return three colors for the color gradient
+// return the colors for the color gradient
 
-pub fn return_colors (style: &ColorStyle, path_opt: Option<String>) -> [Color; 3] {
-        
-    match style {
-        ColorStyle::Bookworm => [
+pub fn return_colors (style: &ColorStyle, path_opt: Option<String>) -> Result<Vec<Color>, Box<dyn std::error::Error>> {
+
+    let colors = match style {
+        ColorStyle::Bookworm => vec![
             Color::from_rgba8(5, 71, 92, 255),
             Color::from_rgba8(10, 120, 115, 255),
             Color::from_rgba8(184, 216, 215, 255)
         ],
-        ColorStyle::Jellyfish => [
+        ColorStyle::Jellyfish => vec![
             Color::from_rgba8(38, 0, 24, 255),
             Color::from_rgba8(90, 25, 63, 255),
             Color::from_rgba8(198, 70, 72, 255)
         ],
-        ColorStyle::Ten => [
+        ColorStyle::Ten => vec![
             Color::from_rgba8(4, 62, 185, 255),
             Color::from_rgba8(2, 123, 230, 255),
             Color::from_rgba8(105, 254, 255, 255)
         ],
-        ColorStyle::Greyscale => [
+        ColorStyle::Greyscale => vec![
             Color::from_rgba8(255, 255, 255, 255),
             Color::from_rgba8(127, 127, 127, 255),
             Color::from_rgba8(0, 0, 0, 255)
         ],
-        ColorStyle::Eleven => [
+        ColorStyle::Eleven => vec![
             Color::from_rgba8(2, 70, 217, 255),
             Color::from_rgba8(1, 214, 244, 255),
             Color::from_rgba8(209, 229, 254, 255),
         ],
-        ColorStyle::Mint => [
+        ColorStyle::Mint => vec![
             Color::from_rgba8(21, 21, 21, 255),
             Color::from_rgba8(137, 184, 70, 255),
             Color::from_rgba8(214, 214, 214, 255),
         ],
-        ColorStyle::Chameleon => [
+        ColorStyle::Chameleon => vec![
             Color::from_rgba8(11, 127, 109, 255),
             Color::from_rgba8(35, 145, 108, 255),
             Color::from_rgba8(21, 155, 110, 255),
         ],
-        ColorStyle::Plasma => [
+        ColorStyle::Plasma => vec![
             Color::from_rgba8(35, 37, 83, 255),
             Color::from_rgba8(36, 102, 156, 255),
             Color::from_rgba8(219, 135, 75, 255),
         ],
-        ColorStyle::Plasma2 => [
+        ColorStyle::Plasma2 => vec![
             Color::from_rgba8(0, 87, 139, 255),
             Color::from_rgba8(0, 147, 235, 255),
             Color::from_rgba8(249, 249, 249, 255),
         ],
-        ColorStyle::Christmas => [
+        ColorStyle::Christmas => vec![
             Color::from_rgba8(31, 56, 35, 255),
             Color::from_rgba8(209, 27, 79, 255),
             Color::from_rgba8(250, 219, 82, 255),
         ],
-        ColorStyle::Config => get_colors_from_file(path_opt).expect("error parsing colors from file"),
-        ColorStyle::Random => get_random_colors().expect("error getting random colors"),
-    }
+        ColorStyle::Config => get_colors_from_file(path_opt)?,
+        ColorStyle::Random => get_random_colors()?,
+    };
 
+    Ok(colors)
 }
 
-// get three colors from csv file - basic attempt
+// get colors from csv file - one row per color, any number of rows
 
-fn get_colors_from_file(path_opt: Option<String>) -> Result<[Color; 3], Box<dyn std::error::Error>> {
+fn get_colors_from_file(path_opt: Option<String>) -> Result<Vec<Color>, Box<dyn std::error::Error>> {
 
     let filename = match path_opt {
         Some(path) => path,
@@ -239,26 +496,20 @@ fn get_colors_from_file(path_opt: Option<String>) -> Result<[Color; 3], Box<dyn
 
     eprintln!("config file: '{}'", &filename);
 
-    let mut output: [Color; 3] = [
-        Color::from_rgba8(0,0,0,0),
-        Color::from_rgba8(0,0,0,0),
-        Color::from_rgba8(0,0,0,0)
-    ];
-
     let strings: Vec<String> = read_to_string(filename)?.lines().skip(1).map(String::from).collect();
 
-    assert!(strings.len() == 3);
+    let mut output: Vec<Color> = Vec::with_capacity(strings.len());
 
-    for string in strings.iter().enumerate() {
+    for string in strings.iter() {
 
-        let mut iterator = string.1.split(',');
+        let mut iterator = string.split(',');
 
-            output[string.0] = Color::from_rgba8(
-                iterator.next().unwrap_or("0").parse()?,
-                iterator.next().unwrap_or("0").parse()?,
-                iterator.next().unwrap_or("0").parse()?,
-                255
-            );
+        output.push(Color::from_rgba8(
+            iterator.next().unwrap_or("0").parse()?,
+            iterator.next().unwrap_or("0").parse()?,
+            iterator.next().unwrap_or("0").parse()?,
+            255
+        ));
 
     }
 
@@ -267,7 +518,7 @@ fn get_colors_from_file(path_opt: Option<String>) -> Result<[Color; 3], Box<dyn
 
 // get three random colors
 
-fn get_random_colors() -> Result<[Color; 3], Box<getrandom::Error>> {
+fn get_random_colors() -> Result<Vec<Color>, Box<dyn std::error::Error>> {
 
     let mut random_data = [0u8; 9];
 
@@ -278,7 +529,7 @@ fn get_random_colors() -> Result<[Color; 3], Box<getrandom::Error>> {
               random_data[3], random_data[4], random_data[5],
               random_data[6], random_data[7], random_data[8]);
 
-    let output = [
+    let output = vec![
         Color::from_rgba8(random_data[0], random_data[1], random_data[2], 255),
         Color::from_rgba8(random_data[3], random_data[4], random_data[5], 255),
         Color::from_rgba8(random_data[6], random_data[7], random_data[8], 255)
@@ -286,3 +537,117 @@ fn get_random_colors() -> Result<[Color; 3], Box<getrandom::Error>> {
 
     Ok(output)
 }
+
+// parse a '--stops' argument ('pos=#hex' or '#hex' entries separated by ',' or ';')
+// into a list of (position, color) stops; positions left unspecified are
+// normalized to an even spread across [0.0, 1.0]
+
+pub fn parse_stops(s: &str) -> Result<Vec<(f64, Color)>, Box<dyn std::error::Error>> {
+
+    let entries: Vec<&str> = s.split(|c| c == ',' || c == ';').map(str::trim).filter(|e| !e.is_empty()).collect();
+
+    let parsed: Vec<(Option<f64>, Color)> = entries.iter().map(|entry| {
+        match entry.split_once('=') {
+            Some((pos, hex)) => Ok((Some(pos.parse::<f64>()?), Color::from_html(hex)?)),
+            None => Ok((None, Color::from_html(entry)?)),
+        }
+    }).collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+    let count = parsed.len();
+
+    let stops = parsed.into_iter().enumerate().map(|(i, (pos, color))| {
+        let position = pos.unwrap_or_else(|| if count <= 1 { 0.0 } else { i as f64 / (count - 1) as f64 });
+
+        (position, color)
+    }).collect();
+
+    Ok(stops)
+}
+
+// build the color gradient used to colorize a render: '--stops', when given, overrides
+// '--color-style'/'--config' and makes the domain position-aware instead of an even
+// [0.0, 255.0] spread
+
+pub fn build_gradient(stops: &Option<String>, style: &ColorStyle, config: Option<String>, blend: &BlendMode) -> Result<colorgrad::Gradient, Box<dyn std::error::Error>> {
+
+    let (color_array, domain) = match stops {
+        Some(stops) => {
+            let parsed = parse_stops(stops)?;
+            let colors: Vec<_> = parsed.iter().map(|(_, color)| color.clone()).collect();
+            let domain: Vec<f64> = parsed.iter().map(|(pos, _)| pos * 255.0).collect();
+
+            (colors, domain)
+        },
+        None => (return_colors(style, config)?, vec![0.0, 255.0]),
+    };
+
+    let grad = colorgrad::CustomGradient::new()
+        .colors(&color_array)
+        .domain(&domain)
+        .mode(to_colorgrad_mode(blend))
+        .build()?;
+
+    Ok(grad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stops_normalizes_missing_positions_to_an_even_spread() {
+        let stops = parse_stops("#05475c,#0a7873,#b8d8d7").unwrap();
+
+        let positions: Vec<f64> = stops.iter().map(|(pos, _)| *pos).collect();
+
+        assert_eq!(positions, vec![0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn parse_stops_keeps_explicit_positions() {
+        let stops = parse_stops("0.0=#05475c;0.4=#0a7873;1.0=#b8d8d7").unwrap();
+
+        let positions: Vec<f64> = stops.iter().map(|(pos, _)| *pos).collect();
+
+        assert_eq!(positions, vec![0.0, 0.4, 1.0]);
+    }
+
+    #[test]
+    fn parse_stops_accepts_comma_and_semicolon_separators_and_skips_empty_entries() {
+        let stops = parse_stops(",#05475c;;#b8d8d7,").unwrap();
+
+        assert_eq!(stops.len(), 2);
+    }
+
+    #[test]
+    fn parse_stops_rejects_malformed_input() {
+        assert!(parse_stops("0.0=not-a-color").is_err());
+        assert!(parse_stops("not-a-number=#05475c").is_err());
+    }
+
+    #[test]
+    fn rank_transform_spreads_distinct_values_evenly_across_the_domain() {
+        let field = [3.0, 1.0, 2.0, 4.0];
+
+        let ranked = rank_transform(&field);
+
+        assert_eq!(ranked, vec![127.5, 0.0, 63.75, 191.25]);
+    }
+
+    #[test]
+    fn rank_transform_ignores_non_finite_values() {
+        let field = [1.0, f64::NAN, 2.0, f64::INFINITY];
+
+        let ranked = rank_transform(&field);
+
+        assert_eq!(ranked[0], 0.0);
+        assert_eq!(ranked[2], 127.5);
+    }
+
+    #[test]
+    fn rank_transform_of_empty_field_is_empty() {
+        let field: [f64; 0] = [];
+
+        assert!(rank_transform(&field).is_empty());
+    }
+}
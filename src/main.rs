@@ -1,15 +1,31 @@
 use clap::Parser;
+use clap::Subcommand;
 use juliafatou::*;
 use juliafatou::ColorStyle;
+use juliafatou::BlendMode;
+use juliafatou::animate::AnimateArgs;
+use juliafatou::serve::ServeArgs;
 use std::sync::Arc;
 use std::thread::available_parallelism;
 use std::time::Instant;
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// render an interpolated frame sequence (numbered PNGs or an animated GIF)
+    Animate(AnimateArgs),
+
+    /// start an on-demand HTTP rendering service
+    Serve(ServeArgs),
+}
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about="render julia sets blazingly fast")]
 
 struct Arguments {
 
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     #[clap(short, long="dimensions", default_value="1200x1200", value_name="USIZExUSIZE")]
     /// Image dimensions
     dimensions: String,
@@ -69,6 +85,22 @@ struct Arguments {
     #[clap(long, default_value_t=false)]
     /// measure render time
     take_time: bool,
+
+    #[clap(short='n', long="samples", default_value_t=1, value_name="USIZE")]
+    /// supersample each pixel on an n x n sub-pixel grid to smooth jagged edges
+    samples: usize,
+
+    #[clap(long="blend", value_enum, default_value="rgb")]
+    /// color space used to interpolate the gradient
+    blend: BlendMode,
+
+    #[clap(long, value_name="STOPS")]
+    /// inline gradient stops, e.g. '0.0=#05475c;0.4=#0a7873;1.0=#b8d8d7' (overrides --color-style)
+    stops: Option<String>,
+
+    #[clap(long, default_value_t=false)]
+    /// histogram-equalize escape times for even color utilization across the gradient
+    equalize: bool,
 }
 
 
@@ -77,15 +109,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // parse arguments
     let args = Arguments::parse();
 
+    // dispatch to a subcommand if one was given
+    match args.command {
+        Some(Command::Animate(anim_args)) => return juliafatou::animate::run(anim_args),
+        Some(Command::Serve(serve_args)) => return juliafatou::serve::run(serve_args),
+        None => {},
+    }
+
     // parse complex number
     let complex = parse_complex_number(&args.complex).expect("error parsing complex number");
 
     // parse image dimensions
     let dimensions: (usize, usize) = parse_values(&args.dimensions, 'x').expect("error parsing image dimensions");
 
-    // scalex is used for both x and y axis in order to mitigate image distortion
-    let scalex = args.scale / dimensions.1 as f64;
-    
     // get x/y ratio of the image dimensions
     let ratio = dimensions.0 as f64 / dimensions.1 as f64;
     
@@ -96,28 +132,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let off = args.scale / 2.0;
     let offset = ((parsed_offset.0 - off) + off * ratio, parsed_offset.1, off);
 
-    // initialize image buffer
-    let mut pixels = vec![0u8; dimensions.0 * dimensions.1 * 3];
-
     // determine number of threads
     let threads = match args.threads {
         Some(value) => value,
         None => available_parallelism()?.get()
     };
     eprintln!("Using {} threads.", threads);
-    
-    // determine maximum number of pixel rows per thread
-    let rows_per_band = dimensions.1 / threads + 1;
-
-    // get the colors that are used to build the color gradient
-    let color_array = return_colors(&args.cm, args.config);
 
     // build color gradient
-    let grad = colorgrad::CustomGradient::new()
-        .colors(&color_array)
-        .domain(&[0.0, 255.0])
-        .mode(colorgrad::BlendMode::Rgb)
-        .build()?;
+    let grad = build_gradient(&args.stops, &args.cm, args.config, &args.blend)?;
 
     // initialize atomic reference counting for the color gradient
     // in order to be shared safely between threads
@@ -131,45 +154,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         begin = Some(Instant::now());
     }
 
-    // initialize scoped multithreading
-    // every thread must know it's bounds and position in the overall image,
-    // as well as the information that defines the corresponding julia set
-    {
-        let bands: Vec<&mut [u8]> = 
-            
-            pixels.chunks_mut(rows_per_band * dimensions.0 * 3).collect();
-        
-        crossbeam::scope(|spawner| {
-            
-            for (i, band) in bands.into_iter().enumerate() {
-                
-                let top = rows_per_band * i;
-                
-                let height = band.len() / dimensions.0 / 3;
-                
-                let band_upper_left = (0, top);
-
-                let band_bounds = (dimensions.0, height);
-                
-                let cloned_arc = Arc::clone(&grad_arc);
-
-                spawner.spawn(move |_| {
-                        render(band,
-                               band_bounds,
-                               band_upper_left,
-                               (scalex, scalex),
-                               offset,
-                               complex,
-                               args.gap,
-                               &cloned_arc,
-                               args.intensity,
-                               args.inverse,
-                               args.power as u32,
-                               args.factor);
-                });
-            }
-        }).unwrap();
-    }
+    // render the single frame across all available threads
+    let pixels = if args.equalize {
+        render_frame_equalized(dimensions,
+                                args.scale,
+                                offset,
+                                complex,
+                                args.gap,
+                                &grad_arc,
+                                args.inverse,
+                                args.power as u32,
+                                args.factor,
+                                args.samples,
+                                threads)
+    } else {
+        render_frame(dimensions,
+                      args.scale,
+                      offset,
+                      complex,
+                      args.gap,
+                      &grad_arc,
+                      args.intensity,
+                      args.inverse,
+                      args.power as u32,
+                      args.factor,
+                      args.samples,
+                      threads)
+    };
 
     // minimalistic post processing
     blur_image(&args.out, &pixels, dimensions, args.blur).expect("error while blurring or writing the image");
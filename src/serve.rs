@@ -0,0 +1,163 @@
+use clap::Args;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::thread::available_parallelism;
+use image::ImageFormat;
+use tiny_http::{Server, Response, Header};
+
+use crate::{render_frame, render_frame_equalized, blur_pixels, build_gradient,
+            parse_complex_number, parse_values, ColorStyle, BlendMode};
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+
+    #[clap(long, default_value="127.0.0.1:8080", value_name="ADDR:PORT")]
+    /// address to bind the HTTP server to
+    bind: String,
+
+    #[clap(long, default_value_t=4_000_000, value_name="USIZE")]
+    /// maximum number of pixels (width * height) allowed per request
+    max_pixels: usize,
+
+    #[clap(long, value_name="USIZE")]
+    /// number of rendering threads per request (optional), defaults to 'available parallelism'
+    threads: Option<usize>,
+}
+
+// percent-decode a query string component, turning '+' into a space
+
+fn percent_decode(s: &str) -> String {
+
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => {
+                let hex: String = chars.by_ref().take(2).collect();
+
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => result.push(byte as char),
+                    Err(_) => result.push('%'),
+                }
+            },
+            '+' => result.push(' '),
+            other => result.push(other),
+        }
+    }
+
+    result
+}
+
+// parse a 'key=value&key=value' query string into a lookup table
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+
+    query.split('&').filter(|pair| !pair.is_empty()).filter_map(|pair| {
+        let mut iterator = pair.splitn(2, '=');
+        let key = iterator.next()?;
+        let value = iterator.next().unwrap_or("");
+
+        Some((percent_decode(key), percent_decode(value)))
+    }).collect()
+}
+
+// render a single PNG from the request parameters, the same parameters the CLI already parses
+
+fn render_png(query: &str, threads: usize, max_pixels: usize) -> Result<Vec<u8>, String> {
+
+    let params = parse_query(query);
+
+    let get = |key: &str, default: &str| params.get(key).cloned().unwrap_or_else(|| default.to_string());
+
+    let dimensions: (usize, usize) = parse_values(&get("dimensions", "800x800"), 'x').ok_or("invalid 'dimensions'")?;
+
+    if dimensions.0.checked_mul(dimensions.1).map_or(true, |total| total > max_pixels) {
+        return Err(format!("requested {}x{} exceeds the {} pixel limit", dimensions.0, dimensions.1, max_pixels));
+    }
+
+    let complex = parse_complex_number(&get("complex", "-0.4,0.6")).ok_or("invalid 'complex'")?;
+    let scale: f64 = get("scale", "3.0").parse().map_err(|_| "invalid 'scale'")?;
+    let parsed_offset: (f64, f64) = parse_values(&get("offset", "0.0:0.0"), ':').ok_or("invalid 'offset'")?;
+    let power: u32 = get("power", "2").parse().map_err(|_| "invalid 'power'")?;
+    let factor: f64 = get("factor", "-0.25").parse().map_err(|_| "invalid 'factor'")?;
+    let gap: f64 = get("gap", "0.01").parse().map_err(|_| "invalid 'gap'")?;
+    let intensity: f64 = get("intensity", "3.0").parse().map_err(|_| "invalid 'intensity'")?;
+    let inverse: bool = get("inverse", "false").parse().unwrap_or(false);
+    let blur: f32 = get("blur", "1.0").parse().map_err(|_| "invalid 'blur'")?;
+    let samples: usize = get("samples", "1").parse().map_err(|_| "invalid 'samples'")?;
+    let equalize: bool = get("equalize", "false").parse().unwrap_or(false);
+
+    let cm = ColorStyle::from_str(&get("color-style", "greyscale"), true)?;
+    let blend = BlendMode::from_str(&get("blend", "rgb"), true)?;
+
+    // get x/y ratio of the image dimensions
+    let ratio = dimensions.0 as f64 / dimensions.1 as f64;
+
+    // calculate actual offset in a way that '0:0' will always result in a centered image
+    let off = scale / 2.0;
+    let offset = ((parsed_offset.0 - off) + off * ratio, parsed_offset.1, off);
+
+    let stops = params.get("stops").cloned();
+
+    let grad = build_gradient(&stops, &cm, None, &blend).map_err(|e| format!("error building gradient: {}", e))?;
+
+    let grad_arc = Arc::new(grad);
+
+    let pixels = if equalize {
+        render_frame_equalized(dimensions, scale, offset, complex, gap, &grad_arc, inverse, power, factor, samples, threads)
+    } else {
+        render_frame(dimensions, scale, offset, complex, gap, &grad_arc, intensity, inverse, power, factor, samples, threads)
+    };
+
+    let blurred = blur_pixels(&pixels, dimensions, blur);
+
+    let mut bytes: Vec<u8> = Vec::new();
+
+    blurred.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).map_err(|e| format!("error encoding PNG: {}", e))?;
+
+    Ok(bytes)
+}
+
+// start the on-demand HTTP rendering service
+
+pub fn run(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+
+    let server = Server::http(&args.bind).map_err(|error| format!("error binding to '{}': {}", args.bind, error))?;
+
+    eprintln!("listening on http://{}", args.bind);
+
+    // determine number of threads used per request
+    let threads = match args.threads {
+        Some(value) => value,
+        None => available_parallelism()?.get()
+    };
+
+    for request in server.incoming_requests() {
+
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (request.url().to_string(), String::new()),
+        };
+
+        if path != "/render" {
+            let _ = request.respond(Response::from_string(format!("unknown path '{}'", path)).with_status_code(404));
+            continue;
+        }
+
+        match render_png(&query, threads, args.max_pixels) {
+            Ok(bytes) => {
+                let header = Header::from_bytes(&b"Content-Type"[..], &b"image/png"[..]).unwrap();
+                let _ = request.respond(Response::from_data(bytes).with_header(header));
+            },
+            Err(message) => {
+                eprintln!("bad request: {}", message);
+                let _ = request.respond(Response::from_string(message).with_status_code(400));
+            },
+        }
+    }
+
+    Ok(())
+}